@@ -1,9 +1,35 @@
+use std::io::IsTerminal;
+
+use crossterm::{
+    execute,
+    style::{Color, ResetColor, SetForegroundColor},
+};
+use num_traits::FromPrimitive;
+
 use crate::lib::{
     date::{Date, Period},
-    entry::Amount,
+    entry::{Amount, Category},
     summary::Summary,
 };
 
+/// Display name for each category, in `Category::from_usize` order
+fn category_labels() -> Vec<String> {
+    (0..Category::COUNT)
+        .map(|i| format!("{:?}", Category::from_usize(i).unwrap()))
+        .collect()
+}
+
+/// Which geometry to draw a period/category breakdown with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PlotKind {
+    /// Categories stacked on top of each other (cumulative prefix sum).
+    StackedArea,
+    /// Categories placed side by side within each period's `atomic_width`.
+    GroupedBars,
+    /// Each category's raw value connected across periods with a line.
+    Lines,
+}
+
 /// In charge of the public interface to the plotting devices
 pub struct Plotter<'d> {
     data: &'d [Summary],
@@ -24,16 +50,57 @@ impl<'d> Plotter<'d> {
 
     /// Launch plotting
     pub fn print_cumulative_plot(&self, title: &str) {
-        self.cumulative_plot()
-            .to_range_group_drawer()
+        self.print_plot(title, PlotKind::StackedArea)
+    }
+
+    /// Like `print_cumulative_plot`, but draws directly in the terminal
+    pub fn print_cumulative_plot_term(&self) {
+        self.print_plot_term(PlotKind::StackedArea)
+    }
+
+    /// Write an SVG plot of the given kind to `<title>.svg`.
+    pub fn print_plot(&self, title: &str, kind: PlotKind) {
+        self.plot(kind)
+            .to_range_group_drawer(category_labels(), kind)
             .render(&format!("{}.svg", title))
     }
 
-    /// Accumulate contained data into cumulative plot
-    fn cumulative_plot(&self) -> Plot<Period, CumulativeEntry<Amount>> {
+    /// Like `print_plot`, but draws directly in the terminal. The terminal
+    /// always draws stacked bands; `kind` only selects cumulative or raw values.
+    pub fn print_plot_term(&self, kind: PlotKind) {
+        self.plot(kind)
+            .to_range_group_drawer(category_labels(), kind)
+            .render_terminal()
+    }
+
+    /// Stacked cumulative plot with a `window`-period moving average overlaid
+    pub fn print_moving_average_plot(&self, title: &str, window: usize) {
+        let trend = self.plot(PlotKind::Lines).windowed(window, |points| {
+            let n = points.len() as isize;
+            let categories = points.first().map(|(_, y)| y.points.len()).unwrap_or(0);
+            let sums = (0..categories)
+                .map(|c| points.iter().map(|(_, y)| y.points[c].0).sum::<isize>())
+                .collect::<Vec<_>>();
+            CumulativeEntry::raw(sums.into_iter().map(|s| Amount(s / n.max(1))).collect())
+        });
+        let mut drawer = self
+            .plot(PlotKind::StackedArea)
+            .to_range_group_drawer(category_labels(), PlotKind::StackedArea);
+        drawer.overlay = Some(trend.to_points());
+        drawer.render(&format!("{}.svg", title))
+    }
+
+    /// Arrange contained data for the requested `kind`
+    fn plot(&self, kind: PlotKind) -> Plot<Period, CumulativeEntry<Amount>> {
         let mut plot = Plot::new();
         for sum in self.data {
-            plot.push(sum.period(), CumulativeEntry::cumul(sum.amounts().to_vec()));
+            let entry = match kind {
+                PlotKind::StackedArea => CumulativeEntry::cumul(sum.amounts().to_vec()),
+                PlotKind::GroupedBars | PlotKind::Lines => {
+                    CumulativeEntry::raw(sum.amounts().to_vec())
+                }
+            };
+            plot.push(sum.period(), entry);
         }
         plot
     }
@@ -56,6 +123,19 @@ impl<X, Y> Plot<X, Y> {
     fn push(&mut self, x: X, y: Y) {
         self.data.push((x, y));
     }
+
+    /// Slide a window of `n` points across the series, anchored at the last `X`
+    fn windowed<Z>(&self, n: usize, f: impl Fn(&[(X, Y)]) -> Z) -> Plot<X, Z>
+    where
+        X: Clone,
+    {
+        let data = self
+            .data
+            .windows(n.max(1))
+            .map(|window| (window.last().unwrap().0.clone(), f(window)))
+            .collect();
+        Plot { data }
+    }
 }
 
 /// Describes how to format a collection of same-abscissa points
@@ -78,6 +158,13 @@ where
     }
 }
 
+impl<Y> CumulativeEntry<Y> {
+    /// Keep the raw, non-cumulative per-category values
+    fn raw(points: Vec<Y>) -> Self {
+        Self { points }
+    }
+}
+
 /// A plot item that can be converted to a value
 /// (e.g. an amount or a date)
 pub trait Scalar {
@@ -137,18 +224,26 @@ where
 
 impl<X, Y> Plot<X, Y>
 where
-    X: ScalarRange,
+    X: ScalarRange + std::fmt::Display,
     Y: ScalarGroup,
 {
-    fn to_range_group_drawer(&self) -> RangeGroupDrawer {
+    /// `labels` names each band for the legend; `kind` picks the geometry
+    fn to_range_group_drawer(&self, labels: Vec<String>, kind: PlotKind) -> RangeGroupDrawer {
         RangeGroupDrawer {
-            points: self
-                .data
-                .iter()
-                .map(|(x, y)| (x.to_range(), y.to_group()))
-                .collect::<Vec<_>>(),
+            points: self.to_points(),
+            labels,
+            kind,
+            overlay: None,
         }
     }
+
+    /// The `(x range, per-category y values, Display of X)` triples a drawer draws from
+    fn to_points(&self) -> Vec<((i64, i64), Vec<i64>, String)> {
+        self.data
+            .iter()
+            .map(|(x, y)| (x.to_range(), y.to_group(), x.to_string()))
+            .collect::<Vec<_>>()
+    }
 }
 
 struct Dimensions {
@@ -220,23 +315,39 @@ impl Dimensions {
 
 #[derive(Debug)]
 struct RangeGroupDrawer {
-    points: Vec<((i64, i64), Vec<i64>)>,
+    /// `(x range, per-category y values, Display of the original X)` per point
+    points: Vec<((i64, i64), Vec<i64>, String)>,
+    /// Category name for each band, in the same order as `COLORS`
+    labels: Vec<String>,
+    /// Which geometry `render` uses; `render_terminal` always draws stacked bands
+    kind: PlotKind,
+    /// An optional dashed trend line drawn on top, only honored by `render_stacked`
+    overlay: Option<Vec<((i64, i64), Vec<i64>, String)>>,
 }
 
 use svg::{
-    node::element::{path::Data, Line, Path},
+    node::element::{path::Data, Line, Path, Rectangle, Text},
+    node::Text as TextNode,
     Document,
 };
 
 impl RangeGroupDrawer {
     fn render(&self, file: &str) {
+        match self.kind {
+            PlotKind::StackedArea => self.render_stacked(file),
+            PlotKind::GroupedBars => self.render_grouped_bars(file),
+            PlotKind::Lines => self.render_lines(file),
+        }
+    }
+
+    fn render_stacked(&self, file: &str) {
         // configure dimensions with extremal values
         let (xmin, ymin, width, height) = {
             let mut xmin = i64::MAX;
             let mut ymin = i64::MAX;
             let mut xmax = i64::MIN;
             let mut ymax = i64::MIN;
-            for ((start, end), points) in &self.points {
+            for ((start, end), points, _) in &self.points {
                 xmin = xmin.min(*start).min(*end);
                 xmax = xmax.max(*start).max(*end);
                 for pt in points {
@@ -262,7 +373,8 @@ impl RangeGroupDrawer {
         let dim = Dimensions::new().with_data(
             self.points
                 .iter()
-                .map(|((start, end), points)| ([start, end], points)),
+                .chain(self.overlay.iter().flatten())
+                .map(|((start, end), points, _)| ([start, end], points)),
         );
         // plot columns one by one
         if self.points.is_empty() {
@@ -280,7 +392,7 @@ impl RangeGroupDrawer {
         let groups_inorder = self
             .points
             .iter()
-            .fold(groups, |gr, ((start, end), points)| {
+            .fold(groups, |gr, ((start, end), points, _)| {
                 gr.into_iter()
                     .enumerate()
                     .map(|(i, gr)| {
@@ -297,7 +409,7 @@ impl RangeGroupDrawer {
             .points
             .iter()
             .rev()
-            .fold(groups_inorder, |gr, ((start, end), points)| {
+            .fold(groups_inorder, |gr, ((start, end), points, _)| {
                 gr.into_iter()
                     .enumerate()
                     .map(|(i, gr)| {
@@ -320,6 +432,111 @@ impl RangeGroupDrawer {
             .into_iter()
             .enumerate()
             .map(|(i, gr)| Path::new().set("fill", COLORS[i]).set("d", gr.close()));
+        let document = paths
+            .into_iter()
+            .fold(Document::new(), |doc, path| doc.add(path));
+        let document = self.add_overlay(document, &dim);
+        self.finish(document, &dim, file);
+    }
+
+    /// Draw `self.overlay`, if present, as one dashed line per category
+    fn add_overlay(&self, document: Document, dim: &Dimensions) -> Document {
+        let overlay = match &self.overlay {
+            Some(overlay) => overlay,
+            None => return document,
+        };
+        let n = overlay.first().map(|(_, ys, _)| ys.len()).unwrap_or(0);
+        (0..n).fold(document, |document, j| {
+            let mut data = None;
+            for ((start, end), ys, _) in overlay {
+                let mid = (*start + *end) / 2;
+                let point = (dim.resize_x(mid), dim.resize_y(ys[j]));
+                data = Some(match data {
+                    None => Data::new().move_to(point),
+                    Some(d) => d.line_to(point),
+                });
+            }
+            match data {
+                Some(data) => {
+                    let path = Path::new()
+                        .set("fill", "none")
+                        .set("stroke", "black")
+                        .set("stroke-width", dim.stroke_width)
+                        .set("stroke-dasharray", "6,4")
+                        .set("d", data);
+                    document.add(path)
+                }
+                None => document,
+            }
+        })
+    }
+
+    /// Draw each period's categories as side-by-side boxes instead of stacking them
+    fn render_grouped_bars(&self, file: &str) {
+        if self.points.is_empty() {
+            return;
+        }
+        let dim = Dimensions::new().with_data(
+            self.points
+                .iter()
+                .map(|((start, end), points, _)| ([start, end], points)),
+        );
+        let n = self.points[0].1.len().max(1);
+        let box_width = dim.atomic_width / n as f64;
+        let mut document = Document::new();
+        for ((start, _end), ys, _) in &self.points {
+            for (j, y) in ys.iter().enumerate() {
+                let x0 = dim.resize_x(*start) + j as f64 * box_width;
+                let y0 = dim.resize_y(0).min(dim.resize_y(*y));
+                let height = (dim.resize_y(0) - dim.resize_y(*y)).abs();
+                let rect = Rectangle::new()
+                    .set("x", x0)
+                    .set("y", y0)
+                    .set("width", box_width)
+                    .set("height", height)
+                    .set("fill", COLORS[j % COLORS.len()]);
+                document = document.add(rect);
+            }
+        }
+        self.finish(document, &dim, file);
+    }
+
+    /// Connect each category's raw value across periods with a line
+    fn render_lines(&self, file: &str) {
+        if self.points.is_empty() {
+            return;
+        }
+        let dim = Dimensions::new().with_data(
+            self.points
+                .iter()
+                .map(|((start, end), points, _)| ([start, end], points)),
+        );
+        let n = self.points[0].1.len().max(1);
+        let mut document = Document::new();
+        for j in 0..n {
+            let mut data = None;
+            for ((start, end), ys, _) in &self.points {
+                let mid = (*start + *end) / 2;
+                let point = (dim.resize_x(mid), dim.resize_y(ys[j]));
+                data = Some(match data {
+                    None => Data::new().move_to(point),
+                    Some(d) => d.line_to(point),
+                });
+            }
+            if let Some(data) = data {
+                let path = Path::new()
+                    .set("fill", "none")
+                    .set("stroke", COLORS[j % COLORS.len()])
+                    .set("stroke-width", dim.stroke_width)
+                    .set("d", data);
+                document = document.add(path);
+            }
+        }
+        self.finish(document, &dim, file);
+    }
+
+    /// The two axis lines common to every plot kind.
+    fn axes(&self, dim: &Dimensions) -> (Line, Line) {
         let yaxis = Line::new()
             .set("x1", dim.resize_x(dim.min_x))
             .set("x2", dim.resize_x(dim.min_x))
@@ -334,22 +551,224 @@ impl RangeGroupDrawer {
             .set("y2", dim.resize_y(0))
             .set("stroke", "black")
             .set("stroke-width", dim.stroke_width);
-        let document = paths
-            .into_iter()
-            .fold(Document::new(), |doc, path| doc.add(path))
-            .add(yaxis)
-            .add(xaxis)
-            .set(
-                "viewBox",
-                (
-                    -dim.margin,
-                    -dim.margin,
-                    dim.view_width + 2.0 * dim.margin,
-                    dim.view_height + 2.0 * dim.margin,
-                ),
-            );
+        (yaxis, xaxis)
+    }
+
+    /// Add axes, gridlines, tick labels and legend, then save to `file`
+    fn finish(&self, document: Document, dim: &Dimensions, file: &str) {
+        let (yaxis, xaxis) = self.axes(dim);
+        let mut document = document.add(yaxis).add(xaxis);
+        for gridline in self.y_gridlines(dim) {
+            document = document.add(gridline);
+        }
+        for tick in self.x_ticks(dim) {
+            document = document.add(tick);
+        }
+        for item in self.legend(dim) {
+            document = document.add(item);
+        }
+        let document = document.set(
+            "viewBox",
+            (
+                -dim.margin,
+                -dim.margin,
+                dim.view_width + LEGEND_WIDTH + 2.0 * dim.margin,
+                dim.view_height + 2.0 * dim.margin,
+            ),
+        );
         svg::save(file, &document).unwrap();
     }
+
+    /// Horizontal gridlines and value labels at "nice" rounded Y intervals.
+    fn y_gridlines(&self, dim: &Dimensions) -> Vec<svg::node::element::Group> {
+        let step = nice_step(dim.delta_y, 6);
+        let mut groups = Vec::new();
+        let mut y = dim.min_y.div_euclid(step) * step;
+        while y <= dim.max_y {
+            if y >= dim.min_y {
+                let line = Line::new()
+                    .set("x1", dim.resize_x(dim.min_x))
+                    .set("x2", dim.resize_x(dim.max_x) + dim.margin / 2.0)
+                    .set("y1", dim.resize_y(y))
+                    .set("y2", dim.resize_y(y))
+                    .set("stroke", "lightgray")
+                    .set("stroke-width", dim.stroke_width / 2.0);
+                let label = Text::new()
+                    .set("x", dim.resize_x(dim.min_x) - 6.0)
+                    .set("y", dim.resize_y(y))
+                    .set("text-anchor", "end")
+                    .set("font-size", 12)
+                    .add(TextNode::new(y.to_string()));
+                groups.push(
+                    svg::node::element::Group::new()
+                        .add(line)
+                        .add(label),
+                );
+            }
+            y += step;
+        }
+        groups
+    }
+
+    /// X-axis tick labels, one per data point
+    fn x_ticks(&self, dim: &Dimensions) -> Vec<Text> {
+        self.points
+            .iter()
+            .map(|((start, _), _, label)| {
+                Text::new()
+                    .set("x", dim.resize_x(*start))
+                    .set("y", dim.resize_y(0) + 16.0)
+                    .set("text-anchor", "middle")
+                    .set("font-size", 11)
+                    .add(TextNode::new(label.clone()))
+            })
+            .collect()
+    }
+
+    /// Legend box, to the right of the chart
+    fn legend(&self, dim: &Dimensions) -> Vec<svg::node::element::Group> {
+        let x = dim.view_width + dim.margin;
+        // stacked band i lies between cumulative values i and i+1, i.e. it is category i+1
+        let skip = if self.kind == PlotKind::StackedArea { 1 } else { 0 };
+        self.labels
+            .iter()
+            .skip(skip)
+            .enumerate()
+            .map(|(i, name)| {
+                let y = i as f64 * 24.0;
+                let swatch = Rectangle::new()
+                    .set("x", x)
+                    .set("y", y)
+                    .set("width", 14)
+                    .set("height", 14)
+                    .set("fill", COLORS.get(i).copied().unwrap_or("black"));
+                let label = Text::new()
+                    .set("x", x + 20.0)
+                    .set("y", y + 12.0)
+                    .set("font-size", 13)
+                    .add(TextNode::new(name.clone()));
+                svg::node::element::Group::new().add(swatch).add(label)
+            })
+            .collect()
+    }
+
+    /// Draw the stacked data as colored columns, falling back to ASCII off-TTY
+    fn render_terminal(&self) {
+        if self.points.is_empty() {
+            return;
+        }
+        let dim = Dimensions::new().with_data(
+            self.points
+                .iter()
+                .map(|((start, end), points, _)| ([start, end], points)),
+        );
+        let tty = std::io::stdout().is_terminal();
+        let term_width = crossterm::terminal::size()
+            .map(|(w, _)| w as usize)
+            .unwrap_or(80);
+        let plot_width = term_width.saturating_sub(GUTTER).max(self.points.len());
+        let col_width = (plot_width / self.points.len()).max(1);
+        let delta_y = dim.delta_y.max(1);
+        let mut stdout = std::io::stdout();
+        for row in 0..TERM_ROWS {
+            let row_top = dim.max_y - (row as i64) * delta_y / TERM_ROWS as i64;
+            let row_bot = dim.max_y - (row as i64 + 1) * delta_y / TERM_ROWS as i64;
+            if row % 4 == 0 {
+                print!("{:>width$} ", row_top, width = GUTTER - 1);
+            } else {
+                print!("{:width$} ", "", width = GUTTER - 1);
+            }
+            for (_, ys, _) in &self.points {
+                let (band, fill) = cell_fill(ys, row_top, row_bot);
+                let ch = if tty { block_char(fill) } else { ascii_char(fill) };
+                if tty {
+                    if let Some(c) = band {
+                        execute!(stdout, SetForegroundColor(color_for(COLORS[c]))).ok();
+                    }
+                }
+                for _ in 0..col_width {
+                    print!("{}", ch);
+                }
+                if tty {
+                    execute!(stdout, ResetColor).ok();
+                }
+            }
+            println!();
+        }
+    }
+}
+
+/// Extra viewBox width reserved on the right for the legend box.
+const LEGEND_WIDTH: f64 = 120.0;
+
+/// Pick a "nice" gridline step (1, 2, or 5 times a power of ten)
+fn nice_step(range: i64, target: usize) -> i64 {
+    if range <= 0 || target == 0 {
+        return 1;
+    }
+    let rough = range as f64 / target as f64;
+    let magnitude = 10f64.powi(rough.log10().floor() as i32);
+    let normalized = rough / magnitude;
+    let step = if normalized < 1.5 {
+        1.0
+    } else if normalized < 3.0 {
+        2.0
+    } else if normalized < 7.0 {
+        5.0
+    } else {
+        10.0
+    };
+    ((step * magnitude).round() as i64).max(1)
+}
+
+/// Width reserved on the left for the Y-axis value labels.
+const GUTTER: usize = 7;
+/// Fixed terminal chart height, in rows.
+const TERM_ROWS: usize = 20;
+/// Eighth-resolution Unicode block characters, from empty to full.
+const BLOCKS: &[char] = &[' ', '▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+
+/// For a terminal row, return the band at its midpoint and its fill fraction
+fn cell_fill(ys: &[i64], row_top: i64, row_bot: i64) -> (Option<usize>, f64) {
+    let lo = *ys.first().unwrap_or(&0);
+    let hi = *ys.last().unwrap_or(&0);
+    let overlap = row_top.min(hi) - row_bot.max(lo);
+    if overlap <= 0 {
+        return (None, 0.0);
+    }
+    let row_height = (row_top - row_bot).max(1);
+    let fill = (overlap as f64 / row_height as f64).clamp(0.0, 1.0);
+    let mid = (row_top.min(hi) + row_bot.max(lo)) / 2;
+    let band = (0..ys.len() - 1).find(|&i| ys[i] <= mid && mid <= ys[i + 1]);
+    (band, fill)
+}
+
+fn block_char(fill: f64) -> char {
+    let idx = (fill * (BLOCKS.len() - 1) as f64).round() as usize;
+    BLOCKS[idx.min(BLOCKS.len() - 1)]
+}
+
+fn ascii_char(fill: f64) -> char {
+    if fill <= 0.0 {
+        ' '
+    } else if fill < 1.0 {
+        '.'
+    } else {
+        '#'
+    }
+}
+
+fn color_for(name: &str) -> Color {
+    match name {
+        "red" => Color::Red,
+        "green" => Color::Green,
+        "blue" => Color::Blue,
+        "yellow" => Color::Yellow,
+        "orange" => Color::DarkYellow,
+        "purple" => Color::Magenta,
+        "cyan" => Color::Cyan,
+        _ => Color::White,
+    }
 }
 
 const COLORS: &[&str] = &["red", "green", "blue", "yellow", "orange", "purple", "cyan"];
@@ -6,7 +6,7 @@ use pest::{
 
 use crate::lib::{
     entry::{self, Entry, Amount, Tag, Span, Category},
-    template::{self, Arg, Instance, models::*},
+    template::{self, Arg, ArgOrDefault, Instance, RecurringInstance, models::*},
     date::{Date, Month},
     error::{ErrorRecord, Error, Loc}
 };
@@ -24,11 +24,17 @@ pub struct BilligParser;
 
 pub type Ast<'i> = Vec<AstItem<'i>>;
 
+// top-level `let NAME = ...` bindings
+pub type ConstPool<'i> = std::collections::HashMap<&'i str, Arg<'i>>;
+
 #[derive(Debug)]
 pub enum AstItem<'i> {
     Entry(Date, Entry),
     Instance(Date, Loc<'i>, Instance<'i>),
     Template(&'i str, Loc<'i>, Template<'i>),
+    Import(&'i str, Loc<'i>),
+    // the range lives in RecurringInstance, not here, since it isn't tied to one calendar day
+    Recurring(Loc<'i>, RecurringInstance<'i>),
 }
 
 pub fn extract<'i>(path: &'i str, errs: &mut ErrorRecord, contents: &'i str) -> Ast<'i> {
@@ -44,28 +50,50 @@ pub fn extract<'i>(path: &'i str, errs: &mut ErrorRecord, contents: &'i str) ->
     validate(path, errs, contents)
 }
 
-// extract contents of wrapper rule
-macro_rules! subrule {
-    ( $node:expr, $rule:expr ) => {{
-        let node = $node;
-        assert_eq!(node.as_rule(), $rule);
-        let mut items = node.into_inner().into_iter();
-        let fst = items
-            .next()
-            .unwrap_or_else(|| panic!("{:?} has no subrule", $rule));
-        if items.next().is_some() {
-            panic!("{:?} has several subrules", $rule);
-        }
-        fst
-    }};
-    ( $node:expr ) => {{
-        let mut items = $node.into_inner().into_iter();
-        let fst = items.next().unwrap_or_else(|| panic!("No subrule"));
-        if items.next().is_some() {
-            panic!("Several subrules");
+// extract, with Import directives spliced in; instanciate requires this
+pub fn extract_resolved<'i>(path: &'i str, errs: &mut ErrorRecord, contents: &'i str) -> Ast<'i> {
+    let ast = extract(path, errs, contents);
+    let mut visited = std::collections::HashSet::new();
+    resolve_imports(path, errs, ast, &mut visited)
+}
+
+// splices imported files in place of the directive; visited catches cycles
+pub fn resolve_imports<'i>(path: &'i str, errs: &mut ErrorRecord, ast: Ast<'i>, visited: &mut std::collections::HashSet<String>) -> Ast<'i> {
+    visited.insert(path.to_string());
+    let mut resolved = Vec::new();
+    for item in ast {
+        match item {
+            AstItem::Import(target, loc) => {
+                if visited.contains(target) {
+                    Error::new("Import cycle")
+                        .with_span(&loc, format!("while importing '{}'", target))
+                        .with_message("This file is already being imported further up the chain")
+                        .register(errs);
+                    continue;
+                }
+                let contents = match std::fs::read_to_string(target) {
+                    Ok(c) => c,
+                    Err(_) => {
+                        Error::new("Missing import")
+                            .with_span(&loc, format!("attempt to import '{}'", target))
+                            .with_message("No such file")
+                            .register(errs);
+                        continue;
+                    }
+                };
+                // Leaked so that the imported AST, which borrows from its own
+                // source text, can be merged into one with a shorter lifetime.
+                let target: &'static str = Box::leak(target.to_string().into_boxed_str());
+                let contents: &'static str = Box::leak(contents.into_boxed_str());
+                let imported = extract(target, errs, contents);
+                let imported = resolve_imports(target, errs, imported, visited);
+                resolved.extend(imported);
+            }
+            other => resolved.push(other),
         }
-        fst
-    }};
+    }
+    visited.remove(path);
+    resolved
 }
 
 // get first and rest of inner
@@ -77,26 +105,37 @@ macro_rules! decapitate {
     }};
 }
 
-// extract two-element inner
-macro_rules! pair {
-    ( $node:expr ) => {{
+// match inner pairs against bindings: `x` required, `x?` optional, `x*` rest (last)
+macro_rules! match_pairs {
+    ( $node:expr; $( $rest:tt )* ) => {{
         let mut items = $node.into_inner().into_iter();
-        let fst = items.next().unwrap_or_else(|| panic!("No 1st"));
-        let snd = items.next().unwrap_or_else(|| panic!("No 2nd"));
-        assert!(items.next().is_none());
-        (fst, snd)
+        match_pairs!(@acc items; (); $( $rest )*)
     }};
-}
 
-// extract three-element inner
-macro_rules! triplet {
-    ( $node:expr ) => {{
-        let mut items = $node.into_inner().into_iter();
-        let fst = items.next().unwrap_or_else(|| panic!("No 1st"));
-        let snd = items.next().unwrap_or_else(|| panic!("No 2nd"));
-        let thr = items.next().unwrap_or_else(|| panic!("No 3rd"));
-        assert!(items.next().is_none());
-        (fst, snd, thr)
+    (@acc $items:ident; ($($out:expr),*); ) => {{
+        if $items.next().is_some() {
+            panic!("Unexpected trailing subrule");
+        }
+        ($($out),*)
+    }};
+    (@acc $items:ident; ($($out:expr),*); $head:ident *) => {
+        ($($out,)* $items)
+    };
+    (@acc $items:ident; ($($out:expr),*); $head:ident ?) => {{
+        let $head = $items.next();
+        match_pairs!(@acc $items; ($($out,)* $head); )
+    }};
+    (@acc $items:ident; ($($out:expr),*); $head:ident ? , $( $tail:tt )*) => {{
+        let $head = $items.next();
+        match_pairs!(@acc $items; ($($out,)* $head); $( $tail )*)
+    }};
+    (@acc $items:ident; ($($out:expr),*); $head:ident) => {{
+        let $head = $items.next().unwrap_or_else(|| panic!("No '{}' subrule", stringify!($head)));
+        match_pairs!(@acc $items; ($($out,)* $head); )
+    }};
+    (@acc $items:ident; ($($out:expr),*); $head:ident , $( $tail:tt )*) => {{
+        let $head = $items.next().unwrap_or_else(|| panic!("No '{}' subrule", stringify!($head)));
+        match_pairs!(@acc $items; ($($out,)* $head); $( $tail )*)
     }};
 }
 
@@ -114,48 +153,69 @@ macro_rules! parse_amount {
     };
 }
 
-// set-once value
-macro_rules! set_or_fail {
-    ( $errs:expr, $var:expr, $val:expr, $name:expr, $loc:expr) => {{
-        if $var.is_some() {
+/// Tracks one set-once field of an entry/template body. Unlike the
+/// `return None`-on-first-error approach it replaces, `set` and `finish`
+/// only ever register a diagnostic into `ErrorRecord`, so the caller can
+/// keep scanning the remaining fields and report every duplicate/missing
+/// field from a single body in one pass.
+struct FieldSlot<'i, T> {
+    name: &'static str,
+    loc: Loc<'i>,
+    slot: Option<T>,
+    seen: bool,
+}
+
+impl<'i, T> FieldSlot<'i, T> {
+    fn new(name: &'static str, loc: Loc<'i>) -> Self {
+        Self { name, loc, slot: None, seen: false }
+    }
+
+    /// Record a value, reporting a "duplicate field" error instead of
+    /// overwriting one that was already set. `val` is `None` when the field
+    /// was present but failed to parse on its own terms (e.g. a type
+    /// mismatch, already reported by the caller) — `finish` then reports
+    /// neither a value nor a spurious "missing field" for it.
+    fn set(&mut self, errs: &mut ErrorRecord, val: Option<T>) {
+        if self.seen {
             Error::new("Duplicate field definition")
-                .with_span(&$loc, format!("attempt to override {}", $name))
+                .with_span(&self.loc, format!("attempt to override {}", self.name))
                 .with_message("Each field may only be defined once")
                 .with_message("Remove this field")
-                .register($errs);
-            return None;
+                .register(errs);
+        } else {
+            self.seen = true;
+            self.slot = val;
         }
-        $var = Some($val);
-    }};
-}
+    }
 
-// non-optional value
-macro_rules! unwrap_or_fail {
-    ( $errs:expr, $val:expr, $name:expr, $loc:expr ) => {{
-        match $val {
-            Some(v) => v,
-            None => {
-                Error::new("Missing field definition")
-                    .with_span(&$loc, format!("'{}' may not be omitted", $name))
-                    .with_message("Each field must be defined once")
-                    .with_message("Add definition for the missing field")
-                    .register($errs);
-                return None;
-            }
+    /// Consume the slot, reporting a "missing field" error if it was never
+    /// set at all.
+    fn finish(self, errs: &mut ErrorRecord) -> Option<T> {
+        if !self.seen {
+            Error::new("Missing field definition")
+                .with_span(&self.loc, format!("'{}' may not be omitted", self.name))
+                .with_message("Each field must be defined once")
+                .with_message("Add definition for the missing field")
+                .register(errs);
         }
-    }};
+        self.slot
+    }
 }
 
 pub fn validate<'i>(path: &'i str, errs: &mut ErrorRecord, pairs: Pairs<'i, Rule>) -> Ast<'i> {
     let mut ast = Vec::new();
+    let mut consts: ConstPool<'i> = ConstPool::new();
     'pairs: for pair in pairs {
         match pair.as_rule() {
             Rule::item => {
                 for item in pair.into_inner() {
                     let loc = (path, item.as_span().clone());
                     match item.as_rule() {
+                        Rule::let_binding => {
+                            register_const(errs, &mut consts, item, &loc);
+                        }
                         Rule::template_descriptor => {
-                            let (name, templ) = match validate_template(path, errs, item) {
+                            let (name, templ) = match validate_template(path, errs, item, &consts) {
                                 Some(x) => x,
                                 None => continue 'pairs,
                             };
@@ -165,7 +225,7 @@ pub fn validate<'i>(path: &'i str, errs: &mut ErrorRecord, pairs: Pairs<'i, Rule
                             let (head, body) = decapitate!(item);
                             assert_eq!(head.as_rule(), Rule::marker_year);
                             let year = parse_usize!(head);
-                            let items = match validate_year(path, errs, year, body.collect::<Vec<_>>()) {
+                            let items = match validate_year(path, errs, year, body.collect::<Vec<_>>(), &consts) {
                                 Some(x) => x,
                                 None => continue 'pairs,
                             };
@@ -173,6 +233,19 @@ pub fn validate<'i>(path: &'i str, errs: &mut ErrorRecord, pairs: Pairs<'i, Rule
                                 ast.push(item);
                             }
                         }
+                        Rule::import => {
+                            let quoted = match_pairs!(item; quoted);
+                            assert_eq!(quoted.as_rule(), Rule::tag_text);
+                            let target = match_pairs!(quoted; text).as_str();
+                            ast.push(AstItem::Import(target, loc));
+                        }
+                        Rule::recurring_entry => {
+                            let res = match read_recurring_entry(path, item, &consts, errs, &loc) {
+                                Some(x) => x,
+                                None => continue 'pairs,
+                            };
+                            ast.push(AstItem::Recurring(loc, res));
+                        }
                         _ => unreachable!(),
                     }
                 }
@@ -184,94 +257,107 @@ pub fn validate<'i>(path: &'i str, errs: &mut ErrorRecord, pairs: Pairs<'i, Rule
     ast
 }
 
-fn validate_template<'i>(path: &'i str, errs: &mut ErrorRecord, pair: Pair<'i, Rule>) -> Option<(&'i str, Template<'i>)> {
+fn register_const<'i>(errs: &mut ErrorRecord, consts: &mut ConstPool<'i>, pair: Pair<'i, Rule>, loc: &Loc<'i>) {
+    let (name, value) = match_pairs!(pair; name, value);
+    assert_eq!(name.as_rule(), Rule::identifier);
+    let name = name.as_str();
+    if let Some(value) = read_value(value, consts, errs, loc) {
+        if consts.contains_key(name) {
+            Error::new("Duplicate constant")
+                .with_span(loc, format!("attempt to redefine '{}'", name))
+                .with_message("Each constant may only be defined once")
+                .with_message("Remove this definition")
+                .register(errs);
+        } else {
+            consts.insert(name, value);
+        }
+    }
+}
+
+fn validate_template<'i>(path: &'i str, errs: &mut ErrorRecord, pair: Pair<'i, Rule>, consts: &ConstPool<'i>) -> Option<(&'i str, Template<'i>)> {
     let loc = (path, pair.as_span().clone());
-    let (id, args, body) = triplet!(pair);
+    let (id, args, body) = match_pairs!(pair; id, args, body);
     assert_eq!(id.as_rule(), Rule::identifier);
     let identifier = id.as_str();
     assert_eq!(args.as_rule(), Rule::template_args);
-    let (positional, named) = read_args(args.into_inner());
+    let (positional, named) = read_args(args.into_inner(), consts, errs, &loc);
     assert_eq!(body.as_rule(), Rule::template_expansion_contents);
-    let mut value: Option<AmountTemplate> = None;
-    let mut cat: Option<Category> = None;
-    let mut span: Option<Span> = None;
-    let mut tag: Option<TagTemplate> = None;
+    let mut value = FieldSlot::new("val", loc.clone());
+    let mut cat = FieldSlot::new("type", loc.clone());
+    let mut span = FieldSlot::new("span", loc.clone());
+    let mut tag = FieldSlot::new("tag", loc.clone());
     for sub in body.into_inner() {
         match sub.as_rule() {
             Rule::template_val => {
-                set_or_fail!(
-                    errs,
-                    value,
-                    read_template_amount(subrule!(subrule!(sub), Rule::template_money_amount)),
-                    "val",
-                    loc
-                );
+                let amount = match_pairs!(sub; child);
+                assert_eq!(amount.as_rule(), Rule::template_money_amount);
+                let v = read_template_amount(amount, consts, errs, &loc);
+                value.set(errs, v);
             }
             Rule::entry_type => {
-                set_or_fail!(errs, cat, read_cat(subrule!(sub)), "type", loc);
+                cat.set(errs, Some(read_cat(match_pairs!(sub; child))));
             }
             Rule::entry_span => {
-                set_or_fail!(errs, span, read_span(subrule!(sub)), "span", loc);
+                span.set(errs, Some(read_span(match_pairs!(sub; child))));
             }
             Rule::template_tag => {
-                set_or_fail!(errs, tag, read_template_tag(subrule!(sub)), "tag", loc);
+                let t = read_template_tag(match_pairs!(sub; child), consts, errs, &loc);
+                tag.set(errs, t);
             }
             _ => unreachable!(),
         }
     }
-    let value = unwrap_or_fail!(errs, value, "val", loc);
-    let cat = unwrap_or_fail!(errs, cat, "cat", loc);
-    let span = unwrap_or_fail!(errs, span, "span", loc);
-    let tag = unwrap_or_fail!(errs, tag, "tag", loc);
-    Some((
-        identifier,
-        Template {
-            positional,
-            named,
-            value,
-            cat,
-            span,
-            tag,
-        },
-    ))
+    let value = value.finish(errs);
+    let cat = cat.finish(errs);
+    let span = span.finish(errs);
+    let tag = tag.finish(errs);
+    match (value, cat, span, tag) {
+        (Some(value), Some(cat), Some(span), Some(tag)) => Some((
+            identifier,
+            Template {
+                positional,
+                named,
+                value,
+                cat,
+                span,
+                tag,
+            },
+        )),
+        _ => None,
+    }
 }
 
-fn read_args<'i>(pairs: Pairs<'i, Rule>) -> (Vec<&'i str>, Vec<(&'i str, Arg<'i>)>) {
+fn read_args<'i>(pairs: Pairs<'i, Rule>, consts: &ConstPool<'i>, errs: &mut ErrorRecord, loc: &Loc<'i>) -> (Vec<(&'i str, Option<Arg<'i>>)>, Vec<(&'i str, Arg<'i>)>) {
     let mut positional = Vec::new();
     let mut named = Vec::new();
     for pair in pairs {
-        match read_arg(pair) {
-            (arg, None) => positional.push(arg),
-            (arg, Some(deflt)) => named.push((arg, deflt)),
+        match pair.as_rule() {
+            Rule::template_positional_arg => positional.push(read_positional_arg(pair, consts, errs, loc)),
+            Rule::template_named_arg => {
+                if let Some(arg) = read_named_arg(pair, consts, errs, loc) {
+                    named.push(arg);
+                }
+            }
+            _ => unreachable!(),
         }
     }
     (positional, named)
 }
 
-fn read_arg<'i>(pair: Pair<'i, Rule>) -> (&'i str, Option<Arg<'i>>) {
-    match pair.as_rule() {
-        Rule::template_positional_arg => {
-            let name = pair.as_str();
-            (name, None)
-        }
-        Rule::template_named_arg => {
-            let (name, default) = pair!(pair);
-            let name = name.as_str();
-            let default = {
-                match default.as_rule() {
-                    Rule::money_amount => Arg::Amount(read_amount(default)),
-                    Rule::tag_text => {
-                        Arg::Tag(subrule!(default, Rule::tag_text).as_str())
-                    }
-                    _ => {
-                        unreachable!()
-                    }
-                }
-            };
-            (name, Some(default))
-        }
-        _ => unreachable!(),
-    }
+// A positional arg is identified by its slot, not its name, but may still
+// declare a default for callers to fall back to with `_`.
+fn read_positional_arg<'i>(pair: Pair<'i, Rule>, consts: &ConstPool<'i>, errs: &mut ErrorRecord, loc: &Loc<'i>) -> (&'i str, Option<Arg<'i>>) {
+    let (name, default) = match_pairs!(pair; name, default?);
+    let name = name.as_str();
+    let default = default.and_then(|d| read_value(d, consts, errs, loc));
+    (name, default)
+}
+
+fn read_named_arg<'i>(pair: Pair<'i, Rule>, consts: &ConstPool<'i>, errs: &mut ErrorRecord, loc: &Loc<'i>) -> Option<(&'i str, Arg<'i>)> {
+    let (name, default) = match_pairs!(pair; name, default);
+    let name = name.as_str();
+    let default = read_value(default, consts, errs, loc)?;
+    Some((name, default))
 }
 
 fn read_amount<'i>(item: Pair<'i, Rule>) -> Amount {
@@ -279,32 +365,61 @@ fn read_amount<'i>(item: Pair<'i, Rule>) -> Amount {
     Amount(parse_amount!(item))
 }
 
-fn read_template_amount<'i>(pair: Pair<'i, Rule>) -> AmountTemplate<'i> {
+fn read_template_amount<'i>(pair: Pair<'i, Rule>, consts: &ConstPool<'i>, errs: &mut ErrorRecord, loc: &Loc<'i>) -> Option<AmountTemplate<'i>> {
     let (sign, pair) = match pair.as_rule() {
-        Rule::builtin_neg => (false, subrule!(pair)),
+        Rule::builtin_neg => (false, match_pairs!(pair; child)),
         _ => (true, pair),
     };
     let items = match pair.as_rule() {
-        Rule::builtin_sum => subrule!(pair)
+        Rule::builtin_sum => match_pairs!(pair; child)
             .into_inner()
             .into_iter()
-            .map(|it| subrule!(it))
+            .map(|it| match_pairs!(it; child))
             .collect::<Vec<_>>(),
         _ => vec![pair],
     };
     let mut sum = Vec::new();
+    let mut ok = true;
     for item in items {
         match item.as_rule() {
             Rule::money_amount => {
                 sum.push(AmountTemplateItem::Cst(read_amount(item)));
             }
+            Rule::const_ref => {
+                let name = match_pairs!(item; child);
+                assert_eq!(name.as_rule(), Rule::identifier);
+                match lookup_const(consts, errs, loc, name.as_str()) {
+                    Some(Arg::Amount(a)) => sum.push(AmountTemplateItem::Cst(a)),
+                    Some(Arg::Tag(_)) => {
+                        Error::new("Type mismatch")
+                            .with_span(loc, "expected an amount here")
+                            .with_message("This constant holds a tag, not an amount")
+                            .register(errs);
+                        ok = false;
+                    }
+                    None => ok = false,
+                }
+            }
             Rule::template_arg_expand => {
-                sum.push(AmountTemplateItem::Arg(subrule!(item).as_str()))
+                sum.push(AmountTemplateItem::Arg(match_pairs!(item; child).as_str()))
             }
             _ => unreachable!(),
         }
     }
-    AmountTemplate { sign, sum }
+    ok.then_some(AmountTemplate { sign, sum })
+}
+
+fn lookup_const<'i>(consts: &ConstPool<'i>, errs: &mut ErrorRecord, loc: &Loc<'i>, name: &str) -> Option<Arg<'i>> {
+    match consts.get(name) {
+        Some(v) => Some(*v),
+        None => {
+            Error::new("Undeclared constant")
+                .with_span(loc, format!("reference to '{}'", name))
+                .with_message("No such top-level 'let' binding")
+                .register(errs);
+            None
+        }
+    }
 }
 
 fn read_cat<'i>(pair: Pair<'i, Rule>) -> Category {
@@ -322,9 +437,9 @@ fn read_cat<'i>(pair: Pair<'i, Rule>) -> Category {
 }
 
 fn read_span<'i>(pair: Pair<'i, Rule>) -> Span {
-    let mut pair = pair.into_inner().into_iter().peekable();
+    let (duration, window, count) = match_pairs!(pair; duration, window?, count?);
     use entry::Duration::*;
-    let duration = match pair.next().unwrap().as_str() {
+    let duration = match duration.as_str() {
         "Day" => Day,
         "Week" => Week,
         "Month" => Month,
@@ -332,27 +447,15 @@ fn read_span<'i>(pair: Pair<'i, Rule>) -> Span {
         _ => unreachable!(),
     };
     use entry::Window::*;
-    let window = pair
-        .peek()
-        .map(|it| {
-            if it.as_rule() == Rule::span_window {
-                Some(match it.as_str() {
-                    "Curr" => Current,
-                    "Post" => Posterior,
-                    "Ante" => Anterior,
-                    "Pred" => Precedent,
-                    "Succ" => Successor,
-                    _ => unreachable!(),
-                })
-            } else {
-                None
-            }
-        })
-        .flatten();
-    if window.is_some() {
-        pair.next();
-    }
-    let count = pair.next().map(|it| parse_usize!(it)).unwrap_or(1);
+    let window = window.map(|it| match it.as_str() {
+        "Curr" => Current,
+        "Post" => Posterior,
+        "Ante" => Anterior,
+        "Pred" => Precedent,
+        "Succ" => Successor,
+        _ => unreachable!(),
+    });
+    let count = count.map(|it| parse_usize!(it)).unwrap_or(1);
     Span {
         duration,
         window: window.unwrap_or(Current),
@@ -360,43 +463,62 @@ fn read_span<'i>(pair: Pair<'i, Rule>) -> Span {
     }
 }
 
-fn read_template_tag<'i>(pair: Pair<'i, Rule>) -> TagTemplate<'i> {
+fn read_template_tag<'i>(pair: Pair<'i, Rule>, consts: &ConstPool<'i>, errs: &mut ErrorRecord, loc: &Loc<'i>) -> Option<TagTemplate<'i>> {
     let concat = match pair.as_rule() {
-        Rule::builtin_concat => subrule!(pair)
+        Rule::builtin_concat => match_pairs!(pair; child)
             .into_inner()
             .into_iter()
-            .map(|it| subrule!(it, Rule::template_string))
+            .map(|it| {
+                assert_eq!(it.as_rule(), Rule::template_string);
+                match_pairs!(it; child)
+            })
             .collect::<Vec<_>>(),
         Rule::tag_text => vec![pair],
         _ => pair.into_inner().into_iter().collect::<Vec<_>>(),
     };
     let mut strs = Vec::new();
+    let mut ok = true;
     use template::TagTemplateItem::*;
     for item in concat {
-        strs.push(match item.as_rule() {
-            Rule::tag_text => Raw(subrule!(item).as_str()),
-            Rule::template_arg_expand => Arg(subrule!(item).as_str()),
-            Rule::template_time => match item.as_str() {
+        match item.as_rule() {
+            Rule::tag_text => strs.push(Raw(match_pairs!(item; child).as_str())),
+            Rule::template_arg_expand => strs.push(Arg(match_pairs!(item; child).as_str())),
+            Rule::const_ref => {
+                let name = match_pairs!(item; child);
+                assert_eq!(name.as_rule(), Rule::identifier);
+                match lookup_const(consts, errs, loc, name.as_str()) {
+                    Some(Arg::Tag(s)) => strs.push(Raw(s)),
+                    Some(Arg::Amount(_)) => {
+                        Error::new("Type mismatch")
+                            .with_span(loc, "expected a tag here")
+                            .with_message("This constant holds an amount, not a tag")
+                            .register(errs);
+                        ok = false;
+                    }
+                    None => ok = false,
+                }
+            }
+            Rule::template_time => strs.push(match item.as_str() {
                 "@Day" => Day,
                 "@Month" => Month,
                 "@Year" => Year,
                 "@Date" => Date,
                 "@Weekday" => Weekday,
                 _ => unreachable!(),
-            },
+            }),
             _ => unreachable!(),
-        });
+        }
     }
-    TagTemplate(strs)
+    ok.then_some(TagTemplate(strs))
 }
 
-fn validate_year<'i>(path: &'i str, errs: &mut ErrorRecord, year: usize, pairs: Vec<Pair<'i, Rule>>) -> Option<Vec<AstItem<'i>>> {
+fn validate_year<'i>(path: &'i str, errs: &mut ErrorRecord, year: usize, pairs: Vec<Pair<'i, Rule>>, consts: &ConstPool<'i>) -> Option<Vec<AstItem<'i>>> {
     let mut v = Vec::new();
     'pairs: for pair in pairs {
         assert_eq!(pair.as_rule(), Rule::entries_month);
         let (month, rest) = decapitate!(pair);
         let month = Month::from(month.as_str());
-        let items = match validate_month(path, errs, year, month, rest.collect::<Vec<_>>()) {
+        let items = match validate_month(path, errs, year, month, rest.collect::<Vec<_>>(), consts) {
             Some(x) => x,
             None => continue 'pairs,
         };
@@ -407,7 +529,7 @@ fn validate_year<'i>(path: &'i str, errs: &mut ErrorRecord, year: usize, pairs:
     Some(v)
 }
 
-fn validate_month<'i>(path: &'i str, errs: &mut ErrorRecord, year: usize, month: Month, pairs: Vec<Pair<'i, Rule>>) -> Option<Vec<AstItem<'i>>> {
+fn validate_month<'i>(path: &'i str, errs: &mut ErrorRecord, year: usize, month: Month, pairs: Vec<Pair<'i, Rule>>, consts: &ConstPool<'i>) -> Option<Vec<AstItem<'i>>> {
     let mut v = Vec::new();
     'pairs: for pair in pairs {
         assert_eq!(pair.as_rule(), Rule::entries_day);
@@ -416,7 +538,7 @@ fn validate_month<'i>(path: &'i str, errs: &mut ErrorRecord, year: usize, month:
         let day = parse_usize!(day);
         match Date::from(year, month, day) {
             Ok(date) => {
-                let items = match validate_day(path, errs, date, rest.collect::<Vec<_>>()) {
+                let items = match validate_day(path, errs, date, rest.collect::<Vec<_>>(), consts) {
                     Some(x) => x,
                     None => continue 'pairs,
                 };
@@ -436,18 +558,22 @@ fn validate_month<'i>(path: &'i str, errs: &mut ErrorRecord, year: usize, month:
     Some(v)
 }
 
-fn validate_day<'i>(path: &'i str, errs: &mut ErrorRecord, date: Date, pairs: Vec<Pair<'i, Rule>>) -> Option<Vec<AstItem<'i>>> {
+fn validate_day<'i>(path: &'i str, errs: &mut ErrorRecord, date: Date, pairs: Vec<Pair<'i, Rule>>, consts: &ConstPool<'i>) -> Option<Vec<AstItem<'i>>> {
     let mut v = Vec::new();
     'pairs: for pair in pairs {
-        let entry = subrule!(pair, Rule::entry);
+        assert_eq!(pair.as_rule(), Rule::entry);
+        let entry = match_pairs!(pair; child);
         let loc = (path, entry.as_span().clone());
         match entry.as_rule() {
             Rule::expand_entry => {
-                let res = read_expand_entry(entry);
+                let res = match read_expand_entry(entry, consts, errs, &loc) {
+                    Some(x) => x,
+                    None => continue 'pairs,
+                };
                 v.push(AstItem::Instance(date.clone(), loc, res));
             }
             Rule::plain_entry => {
-                let res = match validate_plain_entry(path, errs, entry) {
+                let res = match validate_plain_entry(path, errs, entry, consts) {
                     Some(x) => x,
                     None => continue 'pairs,
                 };
@@ -459,75 +585,163 @@ fn validate_day<'i>(path: &'i str, errs: &mut ErrorRecord, date: Date, pairs: Ve
     Some(v)
 }
 
-fn read_expand_entry<'i>(pairs: Pair<'i, Rule>) -> Instance<'i> {
-    let (label, args) = pair!(pairs);
+fn read_expand_entry<'i>(pairs: Pair<'i, Rule>, consts: &ConstPool<'i>, errs: &mut ErrorRecord, loc: &Loc<'i>) -> Option<Instance<'i>> {
+    let (label, args) = match_pairs!(pairs; label, args);
     let label = label.as_str();
+    let (pos, named) = read_instance_args(args.into_inner(), consts, errs, loc)?;
+    Some(Instance { label, pos, named })
+}
+
+// shared by read_expand_entry and read_recurring_entry
+fn read_instance_args<'i>(
+    args: Pairs<'i, Rule>,
+    consts: &ConstPool<'i>,
+    errs: &mut ErrorRecord,
+    loc: &Loc<'i>,
+) -> Option<(Vec<ArgOrDefault<'i>>, Vec<(&'i str, ArgOrDefault<'i>)>)> {
     let mut pos = Vec::new();
     let mut named = Vec::new();
-    for arg in args.into_inner() {
+    let mut ok = true;
+    for arg in args {
         match arg.as_rule() {
             Rule::positional_arg => {
-                pos.push(read_value(subrule!(arg)));
+                match read_value_or_wildcard(match_pairs!(arg; child), consts, errs, loc) {
+                    Some(v) => pos.push(v),
+                    None => ok = false,
+                }
             }
             Rule::named_arg => {
-                let (name, value) = pair!(arg);
+                let (name, value) = match_pairs!(arg; name, value);
                 let name = name.as_str();
-                let value = read_value(subrule!(value));
-                named.push((name, value));
+                match read_value_or_wildcard(match_pairs!(value; child), consts, errs, loc) {
+                    Some(v) => named.push((name, v)),
+                    None => ok = false,
+                }
             }
             _ => unreachable!(),
         }
     }
-    Instance { label, pos, named }
+    ok.then(|| (pos, named))
 }
 
-fn read_value<'i>(pair: Pair<'i, Rule>) -> Arg<'i> {
+// `recur LABEL(args) from DATE to DATE [every N]`, a top-level sibling of import/let
+fn read_recurring_entry<'i>(
+    path: &'i str,
+    pair: Pair<'i, Rule>,
+    consts: &ConstPool<'i>,
+    errs: &mut ErrorRecord,
+    loc: &Loc<'i>,
+) -> Option<RecurringInstance<'i>> {
+    let (label, args, start, end, step) = match_pairs!(pair; label, args, start, end, step?);
+    let label = label.as_str();
+    let (pos, named) = read_instance_args(args.into_inner(), consts, errs, loc)?;
+    let start = read_date_literal(path, errs, start)?;
+    let end = read_date_literal(path, errs, end)?;
+    let step = match step {
+        None => 1,
+        Some(s) => {
+            let span = (path, s.as_span().clone());
+            let n = parse_usize!(s);
+            if n == 0 {
+                Error::new("Invalid step")
+                    .with_span(&span, "zero step here")
+                    .with_message("A recurring entry's step must be at least 1, or it would repeat forever on the same date")
+                    .register(errs);
+                return None;
+            }
+            n
+        }
+    };
+    Some(RecurringInstance { label, start, end, step, pos, named })
+}
+
+// a standalone YEAR-MONTH-DAY, for a recur directive's own date range
+fn read_date_literal<'i>(path: &'i str, errs: &mut ErrorRecord, pair: Pair<'i, Rule>) -> Option<Date> {
+    let loc = (path, pair.as_span().clone());
+    let (year, month, day) = match_pairs!(pair; year, month, day);
+    let year = parse_usize!(year);
+    let month = Month::from(month.as_str());
+    let day = parse_usize!(day);
+    match Date::from(year, month, day) {
+        Ok(date) => Some(date),
+        Err(e) => {
+            Error::new("Invalid date")
+                .with_span(&loc, "defined here")
+                .with_message(format!("{}", e))
+                .register(errs);
+            None
+        }
+    }
+}
+
+fn read_value<'i>(pair: Pair<'i, Rule>, consts: &ConstPool<'i>, errs: &mut ErrorRecord, loc: &Loc<'i>) -> Option<Arg<'i>> {
     match pair.as_rule() {
-        Rule::money_amount => Arg::Amount(read_amount(pair)),
-        Rule::tag_text => Arg::Tag(subrule!(pair).as_str()),
+        Rule::money_amount => Some(Arg::Amount(read_amount(pair))),
+        Rule::tag_text => Some(Arg::Tag(match_pairs!(pair; child).as_str())),
+        Rule::const_ref => {
+            let name = match_pairs!(pair; child);
+            assert_eq!(name.as_rule(), Rule::identifier);
+            lookup_const(consts, errs, loc, name.as_str())
+        }
         _ => {
             unreachable!()
         }
     }
 }
 
-fn validate_plain_entry(path: &str, errs: &mut ErrorRecord, pair: Pair<'_, Rule>) -> Option<Entry> {
+/// Like `read_value`, but also recognizes a bare `_` as an explicit request
+/// to fall back to the template's declared default for that parameter.
+fn read_value_or_wildcard<'i>(pair: Pair<'i, Rule>, consts: &ConstPool<'i>, errs: &mut ErrorRecord, loc: &Loc<'i>) -> Option<ArgOrDefault<'i>> {
+    match pair.as_rule() {
+        Rule::wildcard => Some(ArgOrDefault::Default),
+        _ => read_value(pair, consts, errs, loc).map(ArgOrDefault::Value),
+    }
+}
+
+fn validate_plain_entry<'i>(path: &'i str, errs: &mut ErrorRecord, pair: Pair<'i, Rule>, consts: &ConstPool<'i>) -> Option<Entry> {
     let loc = (path, pair.as_span().clone());
-    let mut value: Option<Amount> = None;
-    let mut cat: Option<Category> = None;
-    let mut span: Option<Span> = None;
-    let mut tag: Option<Tag> = None;
+    let mut value = FieldSlot::new("val", loc.clone());
+    let mut cat = FieldSlot::new("cat", loc.clone());
+    let mut span = FieldSlot::new("span", loc.clone());
+    let mut tag = FieldSlot::new("tag", loc.clone());
     for item in pair.into_inner() {
         match item.as_rule() {
             Rule::entry_val => {
-                set_or_fail!(errs, value, Amount(parse_amount!(subrule!(item))), "val", loc);
+                match read_value(match_pairs!(item; child), consts, errs, &loc) {
+                    Some(Arg::Amount(a)) => value.set(errs, Some(a)),
+                    Some(Arg::Tag(_)) => {
+                        Error::new("Type mismatch")
+                            .with_span(&loc, "expected an amount here")
+                            .with_message("This constant holds a tag, not an amount")
+                            .register(errs);
+                        value.set(errs, None);
+                    }
+                    None => value.set(errs, None),
+                }
             }
             Rule::entry_type => {
-                set_or_fail!(errs, cat, read_cat(subrule!(item)), "cat", loc);
+                cat.set(errs, Some(read_cat(match_pairs!(item; child))));
             }
             Rule::entry_span => {
-                set_or_fail!(errs, span, read_span(subrule!(item)), "span", loc);
+                span.set(errs, Some(read_span(match_pairs!(item; child))));
             }
             Rule::entry_tag => {
-                set_or_fail!(
-                    errs,
-                    tag,
-                    Tag(subrule!(item).into_inner().as_str().to_string()),
-                    "tag",
-                    loc
-                );
+                tag.set(errs, Some(Tag(match_pairs!(item; child).into_inner().as_str().to_string())));
             }
             _ => unreachable!(),
         }
     }
-    let value = unwrap_or_fail!(errs, value, "val", loc);
-    let cat = unwrap_or_fail!(errs, cat, "cat", loc);
-    let span = unwrap_or_fail!(errs, span, "span", loc);
-    let tag = unwrap_or_fail!(errs, tag, "tag", loc);
-    Some(Entry {
-        value,
-        cat,
-        span,
-        tag,
-    })
+    let value = value.finish(errs);
+    let cat = cat.finish(errs);
+    let span = span.finish(errs);
+    let tag = tag.finish(errs);
+    match (value, cat, span, tag) {
+        (Some(value), Some(cat), Some(span), Some(tag)) => Some(Entry {
+            value,
+            cat,
+            span,
+            tag,
+        }),
+        _ => None,
+    }
 }
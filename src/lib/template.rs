@@ -2,9 +2,10 @@ use std::collections::{HashMap, HashSet};
 
 use crate::lib::{
     parse::ast::*,
-    error::{Result, Error},
+    error::{Result, Error, Loc},
     entry::{Entry, fields::*},
     date::Date,
+    veb::VebTree,
 };
 
 pub mod models {
@@ -19,8 +20,19 @@ pub mod models {
 #[derive(Debug)]
 pub struct Instance<'i> {
     pub label: &'i str,
-    pub pos: Vec<Arg<'i>>,
-    pub named: Vec<(&'i str, Arg<'i>)>,
+    pub pos: Vec<ArgOrDefault<'i>>,
+    pub named: Vec<(&'i str, ArgOrDefault<'i>)>,
+}
+
+// expand `label` once per free date in [start, end], step days apart; see expand_recurring
+#[derive(Debug)]
+pub struct RecurringInstance<'i> {
+    pub label: &'i str,
+    pub start: Date,
+    pub end: Date,
+    pub step: usize,
+    pub pos: Vec<ArgOrDefault<'i>>,
+    pub named: Vec<(&'i str, ArgOrDefault<'i>)>,
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -28,9 +40,18 @@ pub enum Arg<'i> {
     Amount(Amount),
     Tag(&'i str),
 }
+
+/// An argument supplied at an instance site: either a concrete value, or a
+/// bare `_` placeholder asking to fall back to the template's declared
+/// default for that parameter.
+#[derive(Debug, Clone, Copy)]
+pub enum ArgOrDefault<'i> {
+    Value(Arg<'i>),
+    Default,
+}
 #[derive(Debug)]
 pub struct Template<'i> {
-    pub positional: Vec<&'i str>,
+    pub positional: Vec<(&'i str, Option<Arg<'i>>)>,
     pub named: Vec<(&'i str, Arg<'i>)>,
     pub value: AmountTemplate<'i>,
     pub cat: Category,
@@ -64,9 +85,11 @@ pub enum AmountTemplateItem<'i> {
     Arg(&'i str),
 }
 
+// expects ast to already have gone through parse::extract_resolved (imports resolved)
 pub fn instanciate(ast: Ast<'_>) -> Result<Vec<(Date, Entry)>> {
     let mut entries = Vec::new();
     let mut templates = HashMap::new();
+    let mut recurring = Vec::new();
     for item in ast {
         match item {
             AstItem::Entry(date, entry) => entries.push((date, entry)),
@@ -77,11 +100,63 @@ pub fn instanciate(ast: Ast<'_>) -> Result<Vec<(Date, Entry)>> {
                 let inst = instanciate_item(instance, date, loc, &templates)?;
                 entries.push((date, inst));
             }
+            AstItem::Recurring(loc, rec) => recurring.push((loc, rec)),
+            AstItem::Import(..) => unreachable!("imports must be resolved before instanciate"),
+        }
+    }
+    if !recurring.is_empty() {
+        let occupied = occupied_dates(&entries, &recurring);
+        for (loc, rec) in recurring {
+            for (date, entry) in expand_recurring(&rec, loc, &templates, &occupied)? {
+                entries.push((date, entry));
+            }
         }
     }
     Ok(entries)
 }
 
+// dates already taken by an explicit entry, sized for every entry and recurring range
+fn occupied_dates(entries: &[(Date, Entry)], recurring: &[(Loc, RecurringInstance)]) -> VebTree {
+    let max_index = entries
+        .iter()
+        .map(|(date, _)| date.index())
+        .chain(recurring.iter().map(|(_, rec)| rec.end.index()))
+        .max()
+        .unwrap_or(0);
+    let mut occupied = VebTree::new(max_index + 1);
+    for (date, _) in entries {
+        occupied.insert(date.index());
+    }
+    occupied
+}
+
+// walks the fixed start + k*step grid, skipping any candidate already occupied
+fn expand_recurring(
+    rec: &RecurringInstance,
+    loc: Loc,
+    templates: &HashMap<String, (pest::Span, Template)>,
+    occupied: &VebTree,
+) -> Result<Vec<(Date, Entry)>> {
+    let (_, loc) = loc;
+    let end_index = rec.end.index();
+    let mut out = Vec::new();
+    let mut d = rec.start.index();
+    while d <= end_index {
+        if !occupied.member(d) {
+            let date = Date::from_index(d);
+            let instance = Instance {
+                label: rec.label,
+                pos: rec.pos.clone(),
+                named: rec.named.clone(),
+            };
+            let entry = instanciate_item(instance, date, loc.clone(), templates)?;
+            out.push((date, entry));
+        }
+        d += rec.step;
+    }
+    Ok(out)
+}
+
 fn instanciate_item(
     instance: Instance<'_>,
     date: Date,
@@ -113,20 +188,47 @@ fn build_arguments<'i>(
             .with_message("Fix the count mismatch");
         return Err(err);
     }
+    // defaults declared by the template, for `_` placeholders to fall back to
+    let defaults: HashMap<&str, Arg> = template.1.named.iter().cloned()
+        .chain(template.1.positional.iter().filter_map(|(name, d)| Some((*name, (*d)?))))
+        .collect();
     let mut args = HashMap::new();
-    for (name, val) in template.1.positional.iter().zip(instance.pos.iter()) {
-        args.insert(name.to_string(), *val);
+    for ((name, _), val) in template.1.positional.iter().zip(instance.pos.iter()) {
+        let resolved = resolve_arg(name, *val, &defaults, loc, template)?;
+        args.insert(name.to_string(), resolved);
     }
     // template first so that instance overrides them
     for (name, val) in template.1.named.iter() {
         args.insert(name.to_string(), *val);
     }
     for (name, val) in instance.named.iter() {
-        args.insert(name.to_string(), *val);
+        let resolved = resolve_arg(name, *val, &defaults, loc, template)?;
+        args.insert(name.to_string(), resolved);
     }
     Ok(args)
 }
 
+/// Resolve a single instance argument, substituting the template's declared
+/// default for a bare `_` placeholder.
+fn resolve_arg<'i>(
+    name: &str,
+    val: ArgOrDefault<'i>,
+    defaults: &HashMap<&str, Arg<'i>>,
+    loc: &pest::Span,
+    template: &(pest::Span<'i>, Template<'i>),
+) -> Result<Arg<'i>> {
+    match val {
+        ArgOrDefault::Value(v) => Ok(v),
+        ArgOrDefault::Default => match defaults.get(name) {
+            Some(d) => Ok(*d),
+            None => Err(Error::new("No default to fall back to")
+                .with_span(loc, format!("'_' used for argument '{}'", name))
+                .with_span(&template.0, "defined here")
+                .with_message("This parameter has no declared default")),
+        },
+    }
+}
+
 fn perform_replacements(
     name: &str,
     loc: &pest::Span,
@@ -244,4 +346,104 @@ fn instanciate_tag(
         }
     }
     Ok((Tag(tag), used))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lib::{entry::{Duration, Window}, date::Month};
+
+    fn dummy_span() -> pest::Span<'static> {
+        pest::Span::new("", 0, 0).unwrap()
+    }
+
+    fn dummy_template() -> (pest::Span<'static>, Template<'static>) {
+        let templ = Template {
+            positional: Vec::new(),
+            named: Vec::new(),
+            value: AmountTemplate { sign: true, sum: vec![AmountTemplateItem::Cst(Amount(0))] },
+            cat: Category::Home,
+            span: Span { duration: Duration::Day, window: Window::Current, count: 1 },
+            tag: TagTemplate(Vec::new()),
+        };
+        (dummy_span(), templ)
+    }
+
+    fn date(y: usize, m: Month, d: usize) -> Date {
+        Date::from(y, m, d).unwrap()
+    }
+
+    fn recurring(start: Date, end: Date, step: usize) -> RecurringInstance<'static> {
+        RecurringInstance { label: "tpl", start, end, step, pos: Vec::new(), named: Vec::new() }
+    }
+
+    #[test]
+    fn expand_recurring_fills_every_free_date() {
+        let mut templates = HashMap::new();
+        templates.insert("tpl".to_string(), dummy_template());
+        let start = date(2024, Month::January, 1);
+        let end = date(2024, Month::January, 5);
+        let rec = recurring(start, end, 1);
+        let occupied = occupied_dates(&[], &[(("", dummy_span()), rec)]);
+        let rec = recurring(start, end, 1);
+        let out = expand_recurring(&rec, ("", dummy_span()), &templates, &occupied).unwrap();
+        assert_eq!(out.len(), 5);
+    }
+
+    #[test]
+    fn expand_recurring_skips_occupied_dates() {
+        let mut templates = HashMap::new();
+        templates.insert("tpl".to_string(), dummy_template());
+        let start = date(2024, Month::January, 1);
+        let end = date(2024, Month::January, 5);
+        let taken = date(2024, Month::January, 3);
+        let entries = vec![(taken, Entry {
+            value: Amount(0),
+            cat: Category::Home,
+            span: Span { duration: Duration::Day, window: Window::Current, count: 1 },
+            tag: Tag(String::new()),
+        })];
+        let rec = recurring(start, end, 1);
+        let occupied = occupied_dates(&entries, &[(("", dummy_span()), recurring(start, end, 1))]);
+        let out = expand_recurring(&rec, ("", dummy_span()), &templates, &occupied).unwrap();
+        let dates: Vec<_> = out.iter().map(|(d, _)| d.index()).collect();
+        assert_eq!(dates.len(), 4);
+        assert!(!dates.contains(&taken.index()));
+    }
+
+    #[test]
+    fn expand_recurring_honors_step() {
+        let mut templates = HashMap::new();
+        templates.insert("tpl".to_string(), dummy_template());
+        let start = date(2024, Month::January, 1);
+        let end = date(2024, Month::January, 10);
+        let rec = recurring(start, end, 3);
+        let occupied = occupied_dates(&[], &[(("", dummy_span()), recurring(start, end, 3))]);
+        let out = expand_recurring(&rec, ("", dummy_span()), &templates, &occupied).unwrap();
+        // day 1, 4, 7, 10
+        assert_eq!(out.len(), 4);
+    }
+
+    #[test]
+    fn expand_recurring_keeps_cadence_around_unrelated_occupied_date() {
+        let mut templates = HashMap::new();
+        templates.insert("tpl".to_string(), dummy_template());
+        let start = date(2024, Month::January, 1);
+        let end = date(2024, Month::January, 10);
+        // day 2 is never on the start + k*3 grid; it must not shift the cadence
+        let taken = date(2024, Month::January, 2);
+        let entries = vec![(taken, Entry {
+            value: Amount(0),
+            cat: Category::Home,
+            span: Span { duration: Duration::Day, window: Window::Current, count: 1 },
+            tag: Tag(String::new()),
+        })];
+        let rec = recurring(start, end, 3);
+        let occupied = occupied_dates(&entries, &[(("", dummy_span()), recurring(start, end, 3))]);
+        let out = expand_recurring(&rec, ("", dummy_span()), &templates, &occupied).unwrap();
+        let mut dates: Vec<_> = out.iter().map(|(d, _)| d.index()).collect();
+        dates.sort();
+        let expected: Vec<_> = [1, 4, 7, 10].iter().map(|&d| date(2024, Month::January, d).index()).collect();
+        assert_eq!(dates, expected);
+    }
 }
\ No newline at end of file
@@ -0,0 +1,333 @@
+// a van Emde Boas tree over a universe of consecutive usize indices;
+// insert/member/successor/predecessor are all O(log log U)
+
+#[derive(Debug)]
+pub struct VebTree {
+    root: Veb,
+}
+
+impl VebTree {
+    // capacity is rounded up to the next power of two, as the recursive split requires
+    pub fn new(capacity: usize) -> Self {
+        let universe = capacity.max(2).next_power_of_two();
+        Self {
+            root: Veb::new(universe.trailing_zeros()),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.root.is_empty()
+    }
+
+    pub fn insert(&mut self, x: usize) {
+        self.root.insert(x);
+    }
+
+    pub fn member(&self, x: usize) -> bool {
+        self.root.member(x)
+    }
+
+    pub fn min(&self) -> Option<usize> {
+        self.root.min()
+    }
+
+    pub fn max(&self) -> Option<usize> {
+        self.root.max()
+    }
+
+    // smallest element strictly greater than x
+    pub fn successor(&self, x: usize) -> Option<usize> {
+        self.root.successor(x)
+    }
+
+    // largest element strictly smaller than x
+    pub fn predecessor(&self, x: usize) -> Option<usize> {
+        self.root.predecessor(x)
+    }
+
+    // smallest element >= x: x itself if present, else its successor
+    pub fn at_or_after(&self, x: usize) -> Option<usize> {
+        if self.member(x) {
+            Some(x)
+        } else if x == 0 {
+            self.min()
+        } else {
+            self.successor(x - 1)
+        }
+    }
+}
+
+// min is kept out-of-band (never inserted into a cluster), so a single-element vEB still recurses in O(1)
+#[derive(Debug)]
+enum Veb {
+    // universe of size 2 (k <= 1), as a 2-bit mask
+    Base { bits: u8 },
+    Node {
+        lower_bits: u32,
+        min: Option<usize>,
+        max: Option<usize>,
+        summary: Box<Veb>,
+        clusters: Vec<Veb>,
+    },
+}
+
+fn index(high: usize, low: usize, lower_bits: u32) -> usize {
+    (high << lower_bits) | low
+}
+
+impl Veb {
+    fn new(k: u32) -> Self {
+        if k <= 1 {
+            Veb::Base { bits: 0 }
+        } else {
+            let lower_bits = k / 2;
+            let upper_bits = k - lower_bits;
+            let num_clusters = 1usize << upper_bits;
+            Veb::Node {
+                lower_bits,
+                min: None,
+                max: None,
+                summary: Box::new(Veb::new(upper_bits)),
+                clusters: (0..num_clusters).map(|_| Veb::new(lower_bits)).collect(),
+            }
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        match self {
+            Veb::Base { bits } => *bits == 0,
+            Veb::Node { min, .. } => min.is_none(),
+        }
+    }
+
+    fn min(&self) -> Option<usize> {
+        match self {
+            Veb::Base { bits } => {
+                if bits & 0b01 != 0 {
+                    Some(0)
+                } else if bits & 0b10 != 0 {
+                    Some(1)
+                } else {
+                    None
+                }
+            }
+            Veb::Node { min, .. } => *min,
+        }
+    }
+
+    fn max(&self) -> Option<usize> {
+        match self {
+            Veb::Base { bits } => {
+                if bits & 0b10 != 0 {
+                    Some(1)
+                } else if bits & 0b01 != 0 {
+                    Some(0)
+                } else {
+                    None
+                }
+            }
+            Veb::Node { max, .. } => *max,
+        }
+    }
+
+    fn member(&self, x: usize) -> bool {
+        match self {
+            Veb::Base { bits } => (bits >> x) & 1 != 0,
+            Veb::Node {
+                min,
+                lower_bits,
+                clusters,
+                ..
+            } => {
+                if *min == Some(x) {
+                    true
+                } else if min.is_none() {
+                    false
+                } else {
+                    let h = x >> lower_bits;
+                    let l = x & ((1usize << lower_bits) - 1);
+                    clusters[h].member(l)
+                }
+            }
+        }
+    }
+
+    fn insert(&mut self, x: usize) {
+        match self {
+            Veb::Base { bits } => *bits |= 1 << x,
+            Veb::Node {
+                lower_bits,
+                min,
+                max,
+                summary,
+                clusters,
+            } => {
+                if min.is_none() {
+                    *min = Some(x);
+                    *max = Some(x);
+                    return;
+                }
+                if *min == Some(x) {
+                    return;
+                }
+                // Out-of-band min: if `x` would become the new minimum,
+                // insert the *old* minimum into the clusters instead, so
+                // the clusters never contain the current min.
+                let mut x = x;
+                if x < min.unwrap() {
+                    std::mem::swap(&mut x, min.as_mut().unwrap());
+                }
+                let h = x >> *lower_bits;
+                let l = x & ((1usize << *lower_bits) - 1);
+                if clusters[h].is_empty() {
+                    summary.insert(h);
+                }
+                clusters[h].insert(l);
+                if max.map_or(true, |m| x > m) {
+                    *max = Some(x);
+                }
+            }
+        }
+    }
+
+    fn successor(&self, x: usize) -> Option<usize> {
+        match self {
+            Veb::Base { bits } => {
+                if x == 0 && (bits & 0b10) != 0 {
+                    Some(1)
+                } else {
+                    None
+                }
+            }
+            Veb::Node {
+                lower_bits,
+                min,
+                clusters,
+                summary,
+                ..
+            } => {
+                if let Some(m) = *min {
+                    if x < m {
+                        return Some(m);
+                    }
+                }
+                let h = x >> lower_bits;
+                let l = x & ((1usize << lower_bits) - 1);
+                if let Some(cluster_max) = clusters[h].max() {
+                    if l < cluster_max {
+                        let offset = clusters[h].successor(l)?;
+                        return Some(index(h, offset, *lower_bits));
+                    }
+                }
+                let next_cluster = summary.successor(h)?;
+                let offset = clusters[next_cluster].min()?;
+                Some(index(next_cluster, offset, *lower_bits))
+            }
+        }
+    }
+
+    fn predecessor(&self, x: usize) -> Option<usize> {
+        match self {
+            Veb::Base { bits } => {
+                if x == 1 && (bits & 0b01) != 0 {
+                    Some(0)
+                } else {
+                    None
+                }
+            }
+            Veb::Node {
+                lower_bits,
+                min,
+                max,
+                clusters,
+                summary,
+            } => {
+                if let Some(m) = *max {
+                    if x > m {
+                        return Some(m);
+                    }
+                }
+                let h = x >> lower_bits;
+                let l = x & ((1usize << lower_bits) - 1);
+                if let Some(cluster_min) = clusters[h].min() {
+                    if l > cluster_min {
+                        let offset = clusters[h].predecessor(l)?;
+                        return Some(index(h, offset, *lower_bits));
+                    }
+                }
+                match summary.predecessor(h) {
+                    Some(prev_cluster) => {
+                        let offset = clusters[prev_cluster].max()?;
+                        Some(index(prev_cluster, offset, *lower_bits))
+                    }
+                    None => min.filter(|&m| x > m),
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn insert_and_member() {
+        let mut t = VebTree::new(100);
+        for x in [3, 17, 41, 63, 99] {
+            t.insert(x);
+        }
+        for x in [3, 17, 41, 63, 99] {
+            assert!(t.member(x));
+        }
+        for x in [0, 1, 16, 40, 98] {
+            assert!(!t.member(x));
+        }
+    }
+
+    #[test]
+    fn successor_skips_gaps() {
+        let mut t = VebTree::new(64);
+        t.insert(5);
+        t.insert(6);
+        t.insert(20);
+        assert_eq!(t.successor(5), Some(6));
+        assert_eq!(t.successor(6), Some(20));
+        assert_eq!(t.successor(20), None);
+        assert_eq!(t.successor(0), Some(5));
+    }
+
+    #[test]
+    fn predecessor_and_min_max() {
+        let mut t = VebTree::new(64);
+        t.insert(5);
+        t.insert(6);
+        t.insert(20);
+        assert_eq!(t.predecessor(20), Some(6));
+        assert_eq!(t.predecessor(6), Some(5));
+        assert_eq!(t.predecessor(5), None);
+        assert_eq!(t.min(), Some(5));
+        assert_eq!(t.max(), Some(20));
+    }
+
+    #[test]
+    fn at_or_after_returns_self_when_member() {
+        let mut t = VebTree::new(64);
+        t.insert(10);
+        t.insert(30);
+        assert_eq!(t.at_or_after(10), Some(10));
+        assert_eq!(t.at_or_after(11), Some(30));
+        assert_eq!(t.at_or_after(31), None);
+        assert_eq!(t.at_or_after(0), Some(10));
+    }
+
+    #[test]
+    fn empty_tree_has_no_elements() {
+        let t = VebTree::new(16);
+        assert!(t.is_empty());
+        assert_eq!(t.min(), None);
+        assert_eq!(t.max(), None);
+        assert_eq!(t.successor(0), None);
+        assert_eq!(t.at_or_after(0), None);
+    }
+}